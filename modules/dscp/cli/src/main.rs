@@ -1,10 +1,16 @@
 use core::error::Error;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use clap::{ArgAction, CommandFactory, Parser, ValueEnum};
 use clap_complete::CompleteEnv;
 use ipnet::IpNet;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use ptree::TreeBuilder;
-use tonic::transport::Channel;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
 
 use code::{
     AddPrefixesRequest, DscpConfig, RemovePrefixesRequest, SetDscpMarkingRequest, ShowConfigRequest,
@@ -42,6 +48,39 @@ pub struct Cmd {
     /// Log verbosity level.
     #[clap(short, action = ArgAction::Count, global = true)]
     pub verbose: u8,
+
+    /// CA certificate (PEM) used to verify the gateway's TLS certificate. Enables TLS.
+    #[clap(long, global = true)]
+    pub ca_cert: Option<PathBuf>,
+    /// Client certificate (PEM) presented to the gateway for mutual TLS. Requires `--client-key`.
+    #[clap(long, global = true)]
+    pub client_cert: Option<PathBuf>,
+    /// Client private key (PEM) matching `--client-cert`.
+    #[clap(long, global = true)]
+    pub client_key: Option<PathBuf>,
+    /// Domain name to verify the gateway's certificate against, overriding the one implied
+    /// by `--endpoint`.
+    #[clap(long, global = true)]
+    pub tls_domain: Option<String>,
+}
+
+/// TLS settings for the gRPC channel, derived from `Cmd`'s `--ca-cert`/`--client-cert`/
+/// `--client-key`/`--tls-domain` flags. Plaintext is used when none are set.
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    pub ca_cert: Option<PathBuf>,
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    pub tls_domain: Option<String>,
+}
+
+impl TlsOptions {
+    fn is_enabled(&self) -> bool {
+        self.ca_cert.is_some()
+            || self.client_cert.is_some()
+            || self.client_key.is_some()
+            || self.tls_domain.is_some()
+    }
 }
 
 #[derive(Debug, Clone, Parser)]
@@ -49,7 +88,9 @@ pub enum ModeCmd {
     Show(ShowConfigCmd),
     PrefixAdd(AddPrefixesCmd),
     PrefixRemove(RemovePrefixesCmd),
+    PrefixSet(SetPrefixesCmd),
     SetMarking(SetDscpMarkingCmd),
+    Apply(ApplyCmd),
 }
 
 #[derive(Debug, Clone, Parser)]
@@ -77,6 +118,11 @@ pub struct AddPrefixesCmd {
     /// Prefix to be added to the input filter of the DSCP module.
     #[arg(long, short, required = true)]
     pub prefix: Vec<IpNet>,
+
+    /// Snapshot every instance before applying and, on the first RPC error, roll back
+    /// the instances already changed instead of leaving them half-applied.
+    #[arg(long)]
+    pub atomic: bool,
 }
 
 #[derive(Debug, Clone, Parser)]
@@ -92,6 +138,31 @@ pub struct RemovePrefixesCmd {
     /// Prefix to be removed from the input filter of the DSCP module.
     #[arg(long, short, required = true)]
     pub prefix: Vec<IpNet>,
+
+    /// Snapshot every instance before applying and, on the first RPC error, roll back
+    /// the instances already changed instead of leaving them half-applied.
+    #[arg(long)]
+    pub atomic: bool,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct SetPrefixesCmd {
+    /// DSCP module name to operate on.
+    #[arg(long = "cfg", short)]
+    pub config_name: String,
+
+    /// Dataplane instances where the changes should be applied.
+    #[arg(long, short, required = true)]
+    pub instances: Vec<u32>,
+
+    /// Complete desired input-filter prefix set; any existing prefix not listed here is
+    /// removed. Ignored when `--clear` is given.
+    #[arg(long, short, required = false)]
+    pub prefix: Vec<IpNet>,
+
+    /// Remove every existing prefix instead of reconciling to `--prefix`.
+    #[arg(long)]
+    pub clear: bool,
 }
 
 #[derive(Debug, Clone, Parser)]
@@ -111,6 +182,66 @@ pub struct SetDscpMarkingCmd {
     /// DSCP mark value (0-63)
     #[arg(long)]
     pub mark: u32,
+
+    /// Snapshot every instance before applying and, on the first RPC error, roll back
+    /// the instances already changed instead of leaving them half-applied.
+    #[arg(long)]
+    pub atomic: bool,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct ApplyCmd {
+    /// Path to the declarative config file describing the desired prefixes and DSCP
+    /// marking, per config name and dataplane instance (YAML or TOML, picked by extension).
+    #[arg(long = "file", short = 'f')]
+    pub file: PathBuf,
+
+    /// Print the computed reconciliation plan without mutating the dataplane.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Keep running, watch the config file for changes, and reconcile on every edit.
+    #[arg(long)]
+    pub watch: bool,
+
+    /// Output format for the `--dry-run` plan.
+    #[clap(long, value_enum, default_value_t = OutputFormat::Tree)]
+    pub format: OutputFormat,
+}
+
+/// A config file change observed by the `--watch` file watcher.
+#[derive(Debug, Clone)]
+enum ConfigChange {
+    Modified,
+}
+
+/// Declarative desired state for one or more DSCP configs, as read from an `apply` file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DesiredState {
+    pub configs: Vec<DesiredConfig>,
+}
+
+/// Desired state of a single DSCP config name across one or more dataplane instances.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DesiredConfig {
+    /// DSCP module name to reconcile.
+    pub name: String,
+    pub instances: Vec<DesiredInstance>,
+}
+
+/// Desired state of a single dataplane instance: the complete prefix set and, optionally,
+/// the DSCP marking. An omitted `prefixes` list is the empty set, so every existing prefix
+/// is removed. The marking is not reconciled when both `flag` and `mark` are omitted; when
+/// only one is omitted, it keeps its current value (0 if the instance has no marking).
+#[derive(Debug, Clone, Deserialize)]
+pub struct DesiredInstance {
+    pub instance: u32,
+    #[serde(default)]
+    pub prefixes: Vec<IpNet>,
+    #[serde(default)]
+    pub flag: Option<u32>,
+    #[serde(default)]
+    pub mark: Option<u32>,
 }
 
 /// Output format options.
@@ -135,13 +266,21 @@ pub async fn main() {
 }
 
 async fn run(cmd: Cmd) -> Result<(), Box<dyn Error>> {
-    let mut service = DscpService::new(cmd.endpoint).await?;
+    let tls = TlsOptions {
+        ca_cert: cmd.ca_cert,
+        client_cert: cmd.client_cert,
+        client_key: cmd.client_key,
+        tls_domain: cmd.tls_domain,
+    };
+    let mut service = DscpService::new(cmd.endpoint, tls).await?;
 
     match cmd.mode {
         ModeCmd::Show(cmd) => service.show_config(cmd).await,
         ModeCmd::PrefixAdd(cmd) => service.add_prefixes(cmd).await,
         ModeCmd::PrefixRemove(cmd) => service.remove_prefixes(cmd).await,
+        ModeCmd::PrefixSet(cmd) => service.set_prefixes(cmd).await,
         ModeCmd::SetMarking(cmd) => service.set_dscp_marking(cmd).await,
+        ModeCmd::Apply(cmd) => service.apply(cmd).await,
     }
 }
 
@@ -150,8 +289,36 @@ pub struct DscpService {
 }
 
 impl DscpService {
-    pub async fn new(endpoint: String) -> Result<Self, Box<dyn Error>> {
-        let client = DscpServiceClient::connect(endpoint).await?;
+    pub async fn new(endpoint: String, tls: TlsOptions) -> Result<Self, Box<dyn Error>> {
+        let mut endpoint = Endpoint::from_shared(endpoint)?;
+
+        if tls.is_enabled() {
+            let mut tls_config = ClientTlsConfig::new();
+
+            if let Some(ca_cert) = &tls.ca_cert {
+                let ca_cert = std::fs::read(ca_cert)?;
+                tls_config = tls_config.ca_certificate(Certificate::from_pem(ca_cert));
+            }
+
+            match (&tls.client_cert, &tls.client_key) {
+                (Some(client_cert), Some(client_key)) => {
+                    let client_cert = std::fs::read(client_cert)?;
+                    let client_key = std::fs::read(client_key)?;
+                    tls_config = tls_config.identity(Identity::from_pem(client_cert, client_key));
+                }
+                (None, None) => {}
+                _ => return Err("--client-cert and --client-key must be given together".into()),
+            }
+
+            if let Some(domain) = &tls.tls_domain {
+                tls_config = tls_config.domain_name(domain);
+            }
+
+            endpoint = endpoint.tls_config(tls_config)?;
+        }
+
+        let channel = endpoint.connect().await?;
+        let client = DscpServiceClient::new(channel);
         Ok(Self { client })
     }
 
@@ -188,60 +355,435 @@ impl DscpService {
     }
 
     pub async fn add_prefixes(&mut self, cmd: AddPrefixesCmd) -> Result<(), Box<dyn Error>> {
+        let prefixes: Vec<String> = cmd.prefix.iter().map(|p| p.to_string()).collect();
+        let mut snapshots = Vec::new();
+
         for instance in cmd.instances {
-            let request = AddPrefixesRequest {
-                target: Some(TargetModule {
-                    config_name: cmd.config_name.clone(),
-                    dataplane_instance: instance,
-                }),
-                prefixes: cmd.prefix.iter().map(|p| p.to_string()).collect(),
+            let target = TargetModule { config_name: cmd.config_name.clone(), dataplane_instance: instance };
+            let snapshot = if cmd.atomic {
+                match self.snapshot(&target).await {
+                    Ok(snapshot) => Some(snapshot),
+                    Err(err) => {
+                        self.rollback(instance, snapshots).await;
+                        return Err(err);
+                    }
+                }
+            } else {
+                None
             };
+
+            let request = AddPrefixesRequest { target: Some(target.clone()), prefixes: prefixes.clone() };
             log::trace!("AddPrefixesRequest: {request:?}");
-            let response = self.client.add_prefixes(request).await?.into_inner();
-            log::debug!("AddPrefixesResponse: {response:?}");
+            match self.client.add_prefixes(request).await {
+                Ok(response) => log::debug!("AddPrefixesResponse: {:?}", response.into_inner()),
+                Err(err) if cmd.atomic => {
+                    self.rollback(instance, snapshots).await;
+                    return Err(err.into());
+                }
+                Err(err) => return Err(err.into()),
+            }
+
+            if let Some(snapshot) = snapshot {
+                snapshots.push((target, snapshot));
+            }
         }
         Ok(())
     }
 
     pub async fn remove_prefixes(&mut self, cmd: RemovePrefixesCmd) -> Result<(), Box<dyn Error>> {
+        let prefixes: Vec<String> = cmd.prefix.iter().map(|p| p.to_string()).collect();
+        let mut snapshots = Vec::new();
+
         for instance in cmd.instances {
-            let request = RemovePrefixesRequest {
-                target: Some(TargetModule {
-                    config_name: cmd.config_name.clone(),
-                    dataplane_instance: instance,
-                }),
-                prefixes: cmd.prefix.iter().map(|p| p.to_string()).collect(),
+            let target = TargetModule { config_name: cmd.config_name.clone(), dataplane_instance: instance };
+            let snapshot = if cmd.atomic {
+                match self.snapshot(&target).await {
+                    Ok(snapshot) => Some(snapshot),
+                    Err(err) => {
+                        self.rollback(instance, snapshots).await;
+                        return Err(err);
+                    }
+                }
+            } else {
+                None
             };
+
+            let request = RemovePrefixesRequest { target: Some(target.clone()), prefixes: prefixes.clone() };
             log::trace!("RemovePrefixesRequest: {request:?}");
-            let response = self.client.remove_prefixes(request).await?.into_inner();
-            log::debug!("RemovePrefixesResponse: {response:?}");
+            match self.client.remove_prefixes(request).await {
+                Ok(response) => log::debug!("RemovePrefixesResponse: {:?}", response.into_inner()),
+                Err(err) if cmd.atomic => {
+                    self.rollback(instance, snapshots).await;
+                    return Err(err.into());
+                }
+                Err(err) => return Err(err.into()),
+            }
+
+            if let Some(snapshot) = snapshot {
+                snapshots.push((target, snapshot));
+            }
         }
         Ok(())
     }
 
-    pub async fn set_dscp_marking(&mut self, cmd: SetDscpMarkingCmd) -> Result<(), Box<dyn Error>> {
-        // Validate flag value
-        if cmd.flag > 2 {
-            return Err("Invalid flag value (must be 0, 1, or 2)".into());
-        }
+    /// Reconciles a dataplane instance's input-filter prefixes to exactly the supplied
+    /// `--prefix` set (or to the empty set with `--clear`), fetching the current prefixes
+    /// via `show_config` and issuing only the minimal add/remove pair needed to converge.
+    pub async fn set_prefixes(&mut self, cmd: SetPrefixesCmd) -> Result<(), Box<dyn Error>> {
+        let desired_prefixes: HashSet<String> = if cmd.clear {
+            HashSet::new()
+        } else {
+            cmd.prefix.iter().map(|p| p.to_string()).collect()
+        };
+
+        for instance in cmd.instances {
+            let target = TargetModule { config_name: cmd.config_name.clone(), dataplane_instance: instance };
+
+            let request = ShowConfigRequest { target: Some(target.clone()) };
+            log::trace!("show config request on dataplane instance {instance}: {request:?}");
+            let response = self.client.show_config(request).await?.into_inner();
+            log::debug!("show config response on dataplane instance {instance}: {response:?}");
+
+            let current_prefixes: HashSet<String> =
+                response.config.as_ref().map(|c| c.prefixes.iter().cloned().collect()).unwrap_or_default();
+
+            let mut add_prefixes: Vec<String> =
+                desired_prefixes.difference(&current_prefixes).cloned().collect();
+            add_prefixes.sort();
+            let mut remove_prefixes: Vec<String> =
+                current_prefixes.difference(&desired_prefixes).cloned().collect();
+            remove_prefixes.sort();
+
+            if !add_prefixes.is_empty() {
+                let request = AddPrefixesRequest { target: Some(target.clone()), prefixes: add_prefixes };
+                log::trace!("AddPrefixesRequest: {request:?}");
+                let response = self.client.add_prefixes(request).await?.into_inner();
+                log::debug!("AddPrefixesResponse: {response:?}");
+            }
 
-        // Validate mark value (6-bit field)
-        if cmd.mark > 63 {
-            return Err("Invalid mark value (must be 0-63)".into());
+            if !remove_prefixes.is_empty() {
+                let request = RemovePrefixesRequest { target: Some(target), prefixes: remove_prefixes };
+                log::trace!("RemovePrefixesRequest: {request:?}");
+                let response = self.client.remove_prefixes(request).await?.into_inner();
+                log::debug!("RemovePrefixesResponse: {response:?}");
+            }
         }
 
+        Ok(())
+    }
+
+    pub async fn set_dscp_marking(&mut self, cmd: SetDscpMarkingCmd) -> Result<(), Box<dyn Error>> {
+        validate_dscp_marking(cmd.flag, cmd.mark)?;
+
+        let mut snapshots = Vec::new();
+
         for instance in cmd.instances {
+            let target = TargetModule { config_name: cmd.config_name.clone(), dataplane_instance: instance };
+            let snapshot = if cmd.atomic {
+                match self.snapshot(&target).await {
+                    Ok(snapshot) => Some(snapshot),
+                    Err(err) => {
+                        self.rollback(instance, snapshots).await;
+                        return Err(err);
+                    }
+                }
+            } else {
+                None
+            };
+
             let request = SetDscpMarkingRequest {
-                target: Some(TargetModule {
-                    config_name: cmd.config_name.clone(),
-                    dataplane_instance: instance,
-                }),
+                target: Some(target.clone()),
                 dscp_config: Some(DscpConfig { flag: cmd.flag, mark: cmd.mark }),
             };
             log::trace!("SetDscpMarkingRequest: {request:?}");
-            let response = self.client.set_dscp_marking(request).await?.into_inner();
-            log::debug!("SetDscpMarkingResponse: {response:?}");
+            match self.client.set_dscp_marking(request).await {
+                Ok(response) => log::debug!("SetDscpMarkingResponse: {:?}", response.into_inner()),
+                Err(err) if cmd.atomic => {
+                    self.rollback(instance, snapshots).await;
+                    return Err(err.into());
+                }
+                Err(err) => return Err(err.into()),
+            }
+
+            if let Some(snapshot) = snapshot {
+                snapshots.push((target, snapshot));
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn apply(&mut self, cmd: ApplyCmd) -> Result<(), Box<dyn Error>> {
+        let desired = load_desired_state(&cmd.file)?;
+        let (plans, baseline) = self.compute_plan(&desired).await?;
+
+        if cmd.dry_run {
+            return match cmd.format {
+                OutputFormat::Json => print_plan_json(&plans),
+                OutputFormat::Tree => print_plan_tree(&plans),
+            };
+        }
+
+        self.apply_plan(plans).await?;
+
+        if cmd.watch {
+            return self.watch(cmd.file, baseline).await;
+        }
+
+        Ok(())
+    }
+
+    /// Watches `path` for changes and reconciles the dataplane against the new desired
+    /// state on every edit, starting from `baseline` (the per-target prefixes/marking
+    /// already pushed by the initial `apply`). Debounces rapid edits and keeps the
+    /// previous good state on a parse error instead of exiting.
+    ///
+    /// Watches `path`'s parent directory rather than `path` itself and filters events by
+    /// file name: editors that save via an atomic rename (e.g. vim's default `backupcopy`)
+    /// replace the file's inode, which would silently drop a watch placed on the file
+    /// directly after the first edit.
+    async fn watch(
+        &mut self,
+        path: PathBuf,
+        mut baseline: HashMap<(String, u32), ResolvedTarget>,
+    ) -> Result<(), Box<dyn Error>> {
+        let watch_dir =
+            path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+        let file_name = path.file_name().ok_or("config file path has no file name")?.to_owned();
+
+        let (change_tx, mut change_rx) = mpsc::channel(16);
+        let mut watcher: RecommendedWatcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let Ok(event) = event else {
+                return;
+            };
+            if !event.kind.is_modify() && !event.kind.is_create() && !event.kind.is_remove() {
+                return;
+            }
+            if event.paths.iter().any(|p| p.file_name() == Some(file_name.as_os_str())) {
+                let _ = change_tx.blocking_send(ConfigChange::Modified);
+            }
+        })?;
+        watcher.watch(watch_dir, RecursiveMode::NonRecursive)?;
+
+        let (shutdown_tx, mut shutdown_rx) = broadcast::channel(1);
+        tokio::spawn(async move {
+            let _ = tokio::signal::ctrl_c().await;
+            let _ = shutdown_tx.send(());
+        });
+
+        log::info!("watching {} for changes, press Ctrl+C to stop", path.display());
+
+        loop {
+            tokio::select! {
+                _ = shutdown_rx.recv() => {
+                    log::info!("shutdown signal received, stopping watch");
+                    return Ok(());
+                }
+                change = change_rx.recv() => {
+                    if change.is_none() {
+                        return Ok(());
+                    }
+
+                    // Debounce rapid edits: wait briefly and drain any further events
+                    // that arrived in the meantime before reconciling once.
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    while change_rx.try_recv().is_ok() {}
+
+                    let desired = match load_desired_state(&path) {
+                        Ok(desired) => desired,
+                        Err(err) => {
+                            log::error!("failed to parse {}, keeping previous config: {err}", path.display());
+                            continue;
+                        }
+                    };
+
+                    let (plans, resolved) = match diff_against_baseline(&baseline, &desired) {
+                        Ok(result) => result,
+                        Err(err) => {
+                            log::error!("invalid config in {}, keeping previous config: {err}", path.display());
+                            continue;
+                        }
+                    };
+                    if let Err(err) = self.apply_plan(plans).await {
+                        log::error!("failed to reconcile {}: {err}", path.display());
+                        continue;
+                    }
+
+                    baseline = resolved;
+                }
+            }
+        }
+    }
+
+    /// Fetches current state for every target in `desired` and computes the minimal
+    /// set of prefix adds/removes and marking changes needed to converge to it, along
+    /// with the resulting per-target baseline (fed back into `diff_against_baseline` by
+    /// `--watch` so the two routines agree on how a partially-specified marking resolves).
+    async fn compute_plan(
+        &mut self,
+        desired: &DesiredState,
+    ) -> Result<(Vec<TargetPlan>, HashMap<(String, u32), ResolvedTarget>), Box<dyn Error>> {
+        let mut plans = Vec::new();
+        let mut baseline = HashMap::new();
+
+        for config in &desired.configs {
+            for instance in &config.instances {
+                let target = TargetModule {
+                    config_name: config.name.clone(),
+                    dataplane_instance: instance.instance,
+                };
+
+                let request = ShowConfigRequest { target: Some(target.clone()) };
+                log::trace!("show config request on dataplane instance {}: {request:?}", instance.instance);
+                let response = self.client.show_config(request).await?.into_inner();
+                log::debug!("show config response on dataplane instance {}: {response:?}", instance.instance);
+
+                let current_prefixes: HashSet<String> =
+                    response.config.as_ref().map(|c| c.prefixes.iter().cloned().collect()).unwrap_or_default();
+                let desired_prefixes: HashSet<String> =
+                    instance.prefixes.iter().map(|p| p.to_string()).collect();
+
+                let mut add_prefixes: Vec<String> =
+                    desired_prefixes.difference(&current_prefixes).cloned().collect();
+                add_prefixes.sort();
+                let mut remove_prefixes: Vec<String> =
+                    current_prefixes.difference(&desired_prefixes).cloned().collect();
+                remove_prefixes.sort();
+
+                let current_marking = response.config.as_ref().and_then(|c| c.dscp_config);
+                let flag = instance.flag.or(current_marking.map(|m| m.flag)).unwrap_or(0);
+                let mark = instance.mark.or(current_marking.map(|m| m.mark)).unwrap_or(0);
+                let marking = if instance.flag.is_some() || instance.mark.is_some() {
+                    validate_dscp_marking(flag, mark)?;
+                    match current_marking {
+                        Some(current) if current.flag == flag && current.mark == mark => None,
+                        _ => Some(DscpConfig { flag, mark }),
+                    }
+                } else {
+                    None
+                };
+
+                baseline.insert(
+                    (config.name.clone(), instance.instance),
+                    ResolvedTarget { prefixes: desired_prefixes, flag, mark },
+                );
+
+                plans.push(TargetPlan {
+                    config_name: config.name.clone(),
+                    instance: instance.instance,
+                    add_prefixes,
+                    remove_prefixes,
+                    marking,
+                });
+            }
+        }
+
+        Ok((plans, baseline))
+    }
+
+    async fn apply_plan(&mut self, plans: Vec<TargetPlan>) -> Result<(), Box<dyn Error>> {
+        for plan in plans {
+            let target =
+                TargetModule { config_name: plan.config_name.clone(), dataplane_instance: plan.instance };
+
+            if !plan.add_prefixes.is_empty() {
+                let request = AddPrefixesRequest { target: Some(target.clone()), prefixes: plan.add_prefixes };
+                log::trace!("AddPrefixesRequest: {request:?}");
+                let response = self.client.add_prefixes(request).await?.into_inner();
+                log::debug!("AddPrefixesResponse: {response:?}");
+            }
+
+            if !plan.remove_prefixes.is_empty() {
+                let request =
+                    RemovePrefixesRequest { target: Some(target.clone()), prefixes: plan.remove_prefixes };
+                log::trace!("RemovePrefixesRequest: {request:?}");
+                let response = self.client.remove_prefixes(request).await?.into_inner();
+                log::debug!("RemovePrefixesResponse: {response:?}");
+            }
+
+            if let Some(dscp_config) = plan.marking {
+                let request = SetDscpMarkingRequest { target: Some(target), dscp_config: Some(dscp_config) };
+                log::trace!("SetDscpMarkingRequest: {request:?}");
+                let response = self.client.set_dscp_marking(request).await?.into_inner();
+                log::debug!("SetDscpMarkingResponse: {response:?}");
+            }
         }
+
+        Ok(())
+    }
+
+    /// Fetches `target`'s current config, to be restored later by `rollback` if a
+    /// later instance in the same `--atomic` command fails.
+    async fn snapshot(&mut self, target: &TargetModule) -> Result<ShowConfigResponse, Box<dyn Error>> {
+        let request = ShowConfigRequest { target: Some(target.clone()) };
+        log::trace!("snapshot show_config request: {request:?}");
+        let response = self.client.show_config(request).await?.into_inner();
+        log::debug!("snapshot show_config response: {response:?}");
+        Ok(response)
+    }
+
+    /// Reverses every already-applied `(target, snapshot)` pair back to its snapshotted
+    /// state, in reverse application order, after `failed_instance`'s RPC errored. Best
+    /// effort: a rollback failure is logged rather than propagated, since the caller is
+    /// already returning the original error.
+    async fn rollback(&mut self, failed_instance: u32, snapshots: Vec<(TargetModule, ShowConfigResponse)>) {
+        if snapshots.is_empty() {
+            return;
+        }
+
+        log::error!(
+            "instance {failed_instance} failed, rolling back {} already-applied instance(s)",
+            snapshots.len()
+        );
+
+        for (target, snapshot) in snapshots.into_iter().rev() {
+            if let Err(err) = self.rollback_one(&target, &snapshot).await {
+                log::error!("rollback failed for {target:?}: {err}");
+            }
+        }
+    }
+
+    /// Restores a single target to `snapshot` by diffing it against the target's current
+    /// (post-mutation) state and issuing only the prefixes/marking needed to converge.
+    async fn rollback_one(
+        &mut self,
+        target: &TargetModule,
+        snapshot: &ShowConfigResponse,
+    ) -> Result<(), Box<dyn Error>> {
+        let current = self.snapshot(target).await?;
+
+        let current_prefixes: HashSet<String> =
+            current.config.as_ref().map(|c| c.prefixes.iter().cloned().collect()).unwrap_or_default();
+        let snapshot_prefixes: HashSet<String> =
+            snapshot.config.as_ref().map(|c| c.prefixes.iter().cloned().collect()).unwrap_or_default();
+
+        let mut add_prefixes: Vec<String> =
+            snapshot_prefixes.difference(&current_prefixes).cloned().collect();
+        add_prefixes.sort();
+        let mut remove_prefixes: Vec<String> =
+            current_prefixes.difference(&snapshot_prefixes).cloned().collect();
+        remove_prefixes.sort();
+
+        if !add_prefixes.is_empty() {
+            let request = AddPrefixesRequest { target: Some(target.clone()), prefixes: add_prefixes };
+            log::trace!("rollback AddPrefixesRequest: {request:?}");
+            self.client.add_prefixes(request).await?;
+        }
+
+        if !remove_prefixes.is_empty() {
+            let request = RemovePrefixesRequest { target: Some(target.clone()), prefixes: remove_prefixes };
+            log::trace!("rollback RemovePrefixesRequest: {request:?}");
+            self.client.remove_prefixes(request).await?;
+        }
+
+        let snapshot_marking = snapshot.config.as_ref().and_then(|c| c.dscp_config);
+        let current_marking = current.config.as_ref().and_then(|c| c.dscp_config);
+        if snapshot_marking != current_marking {
+            let dscp_config = snapshot_marking.unwrap_or(DscpConfig { flag: 0, mark: 0 });
+            let request = SetDscpMarkingRequest { target: Some(target.clone()), dscp_config: Some(dscp_config) };
+            log::trace!("rollback SetDscpMarkingRequest: {request:?}");
+            self.client.set_dscp_marking(request).await?;
+        }
+
         Ok(())
     }
 
@@ -267,6 +809,113 @@ impl DscpService {
     }
 }
 
+/// Validates a DSCP marking flag/mark pair, shared by the imperative `set-marking` command
+/// and the declarative `apply`/`--watch` reconcile paths so a bad value from a config file
+/// is rejected just as strictly as one passed on the command line.
+fn validate_dscp_marking(flag: u32, mark: u32) -> Result<(), Box<dyn Error>> {
+    if flag > 2 {
+        return Err("Invalid flag value (must be 0, 1, or 2)".into());
+    }
+
+    if mark > 63 {
+        return Err("Invalid mark value (must be 0-63)".into());
+    }
+
+    Ok(())
+}
+
+/// Reads and parses a declarative `apply` config file, picking YAML or TOML by extension
+/// (defaulting to YAML for an unrecognized or missing extension).
+fn load_desired_state(path: &Path) -> Result<DesiredState, Box<dyn Error>> {
+    let content = std::fs::read_to_string(path)?;
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => Ok(toml::from_str(&content)?),
+        _ => Ok(serde_yaml::from_str(&content)?),
+    }
+}
+
+/// Per-target state `--watch` believes it last pushed to the dataplane: the prefix set and
+/// the resolved (never `None`) flag/mark. Kept in lockstep with `compute_plan`'s baseline so
+/// an omitted `flag` or `mark` in a later edit resolves the same way under `--watch` as it
+/// would on a fresh `apply` — inheriting the last-known value rather than resetting to 0.
+#[derive(Debug, Clone)]
+struct ResolvedTarget {
+    prefixes: HashSet<String>,
+    flag: u32,
+    mark: u32,
+}
+
+/// Diffs a freshly-loaded desired state against `baseline` (the state last pushed to the
+/// dataplane), without consulting the live device (used by `--watch` so a reconcile doesn't
+/// require a round-trip `show_config` on every edit). Targets with no change are omitted.
+/// Returns the updated baseline alongside the plan so the caller can feed it back in on the
+/// next edit.
+fn diff_against_baseline(
+    baseline: &HashMap<(String, u32), ResolvedTarget>,
+    desired: &DesiredState,
+) -> Result<(Vec<TargetPlan>, HashMap<(String, u32), ResolvedTarget>), Box<dyn Error>> {
+    let mut plans = Vec::new();
+    let mut resolved = HashMap::new();
+
+    for config in &desired.configs {
+        for instance in &config.instances {
+            let previous = baseline.get(&(config.name.clone(), instance.instance));
+
+            let previous_prefixes = previous.map(|p| &p.prefixes).cloned().unwrap_or_default();
+            let desired_prefixes: HashSet<String> =
+                instance.prefixes.iter().map(|p| p.to_string()).collect();
+
+            let mut add_prefixes: Vec<String> =
+                desired_prefixes.difference(&previous_prefixes).cloned().collect();
+            add_prefixes.sort();
+            let mut remove_prefixes: Vec<String> =
+                previous_prefixes.difference(&desired_prefixes).cloned().collect();
+            remove_prefixes.sort();
+
+            let flag = instance.flag.or(previous.map(|p| p.flag)).unwrap_or(0);
+            let mark = instance.mark.or(previous.map(|p| p.mark)).unwrap_or(0);
+            let marking = if instance.flag.is_some() || instance.mark.is_some() {
+                validate_dscp_marking(flag, mark)?;
+                match previous {
+                    Some(previous) if previous.flag == flag && previous.mark == mark => None,
+                    _ => Some(DscpConfig { flag, mark }),
+                }
+            } else {
+                None
+            };
+
+            resolved.insert(
+                (config.name.clone(), instance.instance),
+                ResolvedTarget { prefixes: desired_prefixes, flag, mark },
+            );
+
+            if add_prefixes.is_empty() && remove_prefixes.is_empty() && marking.is_none() {
+                continue;
+            }
+
+            plans.push(TargetPlan {
+                config_name: config.name.clone(),
+                instance: instance.instance,
+                add_prefixes,
+                remove_prefixes,
+                marking,
+            });
+        }
+    }
+
+    Ok((plans, resolved))
+}
+
+/// Computed reconciliation plan for a single config name on a single dataplane instance.
+#[derive(Debug, Clone, Serialize)]
+pub struct TargetPlan {
+    pub config_name: String,
+    pub instance: u32,
+    pub add_prefixes: Vec<String>,
+    pub remove_prefixes: Vec<String>,
+    pub marking: Option<DscpConfig>,
+}
+
 pub fn print_json(configs: Vec<ShowConfigResponse>) -> Result<(), Box<dyn Error>> {
     println!("{}", serde_json::to_string(&configs)?);
     Ok(())
@@ -302,6 +951,53 @@ pub fn print_tree(configs: Vec<ShowConfigResponse>) -> Result<(), Box<dyn Error>
     Ok(())
 }
 
+pub fn print_plan_json(plans: &[TargetPlan]) -> Result<(), Box<dyn Error>> {
+    println!("{}", serde_json::to_string(plans)?);
+    Ok(())
+}
+
+pub fn print_plan_tree(plans: &[TargetPlan]) -> Result<(), Box<dyn Error>> {
+    let mut tree = TreeBuilder::new("Apply Plan".to_string());
+
+    for plan in plans {
+        tree.begin_child(format!("{} / Instance {}", plan.config_name, plan.instance));
+
+        if plan.add_prefixes.is_empty() && plan.remove_prefixes.is_empty() && plan.marking.is_none() {
+            tree.add_empty_child("(no changes)".to_string());
+        }
+
+        if !plan.add_prefixes.is_empty() {
+            tree.begin_child("Add Prefixes".to_string());
+            for prefix in &plan.add_prefixes {
+                tree.add_empty_child(format!("+ {prefix}"));
+            }
+            tree.end_child();
+        }
+
+        if !plan.remove_prefixes.is_empty() {
+            tree.begin_child("Remove Prefixes".to_string());
+            for prefix in &plan.remove_prefixes {
+                tree.add_empty_child(format!("- {prefix}"));
+            }
+            tree.end_child();
+        }
+
+        if let Some(marking) = &plan.marking {
+            tree.begin_child("Set DSCP Marking".to_string());
+            tree.add_empty_child(format!("Flag: {}", flag_to_string(marking.flag)));
+            tree.add_empty_child(format!("Mark: {} (0x{:02x})", marking.mark, marking.mark));
+            tree.end_child();
+        }
+
+        tree.end_child();
+    }
+
+    let tree = tree.build();
+    ptree::print_tree(&tree)?;
+
+    Ok(())
+}
+
 fn flag_to_string(flag: u32) -> String {
     match flag {
         0 => "Never".to_string(),